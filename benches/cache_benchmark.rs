@@ -1,4 +1,5 @@
 use std::{
+    path::PathBuf,
     sync::Arc,
     time::{Duration, Instant},
 };
@@ -24,6 +25,20 @@ fn gen_pairs(rng: &mut ThreadRng, size: usize) -> Vec<(String, Vec<u8>)> {
         .collect::<Vec<_>>()
 }
 
+fn temp_db_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!(
+        "sqlite_cache_bench_{}_{}.db",
+        name,
+        std::process::id()
+    ))
+}
+
+fn remove_db(path: &PathBuf) {
+    for ext in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{}", path.display(), ext));
+    }
+}
+
 fn bench_get_mt(b: &mut Bencher, size: usize, num_threads: usize) {
     let mut rng = thread_rng();
     let cache = Cache::new(
@@ -66,6 +81,48 @@ fn bench_get(b: &mut Bencher, size: usize) {
     bench_get_mt(b, size, 1)
 }
 
+/// Same workload as `bench_get_mt`, but against a file-backed database so
+/// reads actually go through `ReaderPool::Pool` (and its spill overflow
+/// under enough concurrency) instead of falling back to the single shared
+/// connection `:memory:` databases are stuck with.
+fn bench_get_mt_file_backed(b: &mut Bencher, size: usize, num_threads: usize) {
+    let mut rng = thread_rng();
+    let path = temp_db_path("get_mt_file_backed");
+    let cache = Cache::new(CacheConfig::default(), Connection::open(&path).unwrap()).unwrap();
+    let topic = cache.topic("test").unwrap();
+    let pairs = gen_pairs(&mut rng, size);
+    for (k, v) in &pairs {
+        topic.set(k, v, Duration::from_secs(3600)).unwrap();
+    }
+
+    let pairs = Arc::new(pairs);
+
+    b.iter_custom(|n| {
+        let start = Instant::now();
+        let handles = (0..num_threads)
+            .map(|_| {
+                let pairs = pairs.clone();
+                let topic = topic.clone();
+                std::thread::spawn(move || {
+                    let mut rng = thread_rng();
+                    for _ in 0..n {
+                        let (k, v) = pairs.choose(&mut rng).unwrap();
+                        let got_value = topic.get(k).unwrap().unwrap();
+                        assert_eq!(&got_value.data, v);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for h in handles {
+            h.join().unwrap();
+        }
+        start.elapsed()
+    });
+
+    drop(cache);
+    remove_db(&path);
+}
+
 fn bench_set_mt(b: &mut Bencher, size: usize, num_threads: usize) {
     let mut rng = thread_rng();
     let cache = Cache::new(
@@ -104,18 +161,56 @@ fn bench_set(b: &mut Bencher, size: usize) {
     bench_set_mt(b, size, 1)
 }
 
+const BATCH_INSERT_SIZE: u64 = 100;
+
+/// Same workload as `bench_set`, but through `Topic::batch()` in groups of
+/// `BATCH_INSERT_SIZE`, to compare against the per-row path.
+fn bench_batch_set(b: &mut Bencher, size: usize) {
+    let mut rng = thread_rng();
+    let cache = Cache::new(
+        CacheConfig::default(),
+        Connection::open_in_memory().unwrap(),
+    )
+    .unwrap();
+    let topic = cache.topic("test").unwrap();
+    let pairs = gen_pairs(&mut rng, size);
+
+    b.iter_custom(|n| {
+        let mut rng = thread_rng();
+        let start = Instant::now();
+        let mut remaining = n;
+        while remaining > 0 {
+            let this_batch = remaining.min(BATCH_INSERT_SIZE);
+            let mut batch = topic.batch();
+            for _ in 0..this_batch {
+                let (k, v) = pairs.choose(&mut rng).unwrap();
+                batch = batch.set(k.clone(), v.clone(), Duration::from_secs(3600));
+            }
+            batch.commit().unwrap();
+            remaining -= this_batch;
+        }
+        start.elapsed()
+    });
+}
+
 fn criterion_benchmark(c: &mut Criterion) {
     //c.bench_function("lookup - cache size 1000", |b| bench_get(b, 1000));
     c.bench_function("lookup - cache size 10000", |b| bench_get(b, 10000));
     c.bench_function("lookup mt(4) - cache size 10000", |b| {
         bench_get_mt(b, 10000, 4)
     });
+    c.bench_function("lookup mt(4) file-backed - cache size 10000", |b| {
+        bench_get_mt_file_backed(b, 10000, 4)
+    });
     //c.bench_function("lookup - cache size 50000", |b| bench_get(b, 50000));
     //c.bench_function("insert - cache size 1000", |b| bench_set(b, 1000));
     c.bench_function("insert - cache size 10000", |b| bench_set(b, 10000));
     c.bench_function("insert mt(4) - cache size 10000", |b| {
         bench_set_mt(b, 10000, 4)
     });
+    c.bench_function("batch insert (100/commit) - cache size 10000", |b| {
+        bench_batch_set(b, 10000)
+    });
     //c.bench_function("insert - cache size 50000", |b| bench_set(b, 50000));
 }
 
@@ -0,0 +1,270 @@
+//! Transactional batch operations: collect several `set`/`delete`/
+//! conditional operations on a `Topic` and commit them inside one
+//! `unchecked_transaction()`, instead of paying the writer-mutex and
+//! implicit-transaction cost once per row.
+
+use std::time::Duration;
+
+use crate::{Topic, Value};
+
+enum BatchOp {
+    Set {
+        key: String,
+        value: Vec<u8>,
+        ttl: Duration,
+    },
+    Delete {
+        key: String,
+    },
+    SetIfAbsent {
+        key: String,
+        value: Vec<u8>,
+        ttl: Duration,
+    },
+    CompareAndSet {
+        key: String,
+        value: Vec<u8>,
+        ttl: Duration,
+        predicate: Box<dyn FnOnce(Option<&Value>) -> bool>,
+    },
+}
+
+/// What a single queued operation did once the batch committed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BatchOutcome {
+    /// The row was written.
+    Set,
+    /// The row was deleted.
+    Deleted,
+    /// `set_if_absent`/`compare_and_set` found its condition unmet and left
+    /// the row untouched.
+    Skipped,
+}
+
+/// A builder that queues operations on a `Topic` and commits them all in a
+/// single transaction via [`Batch::commit`].
+pub struct Batch<'a> {
+    topic: &'a Topic,
+    ops: Vec<BatchOp>,
+}
+
+impl<'a> Batch<'a> {
+    fn new(topic: &'a Topic) -> Self {
+        Batch {
+            topic,
+            ops: Vec::new(),
+        }
+    }
+
+    pub fn set(mut self, key: impl Into<String>, value: impl Into<Vec<u8>>, ttl: Duration) -> Self {
+        self.ops.push(BatchOp::Set {
+            key: key.into(),
+            value: value.into(),
+            ttl,
+        });
+        self
+    }
+
+    pub fn delete(mut self, key: impl Into<String>) -> Self {
+        self.ops.push(BatchOp::Delete { key: key.into() });
+        self
+    }
+
+    /// Write `key` only if it doesn't already exist.
+    pub fn set_if_absent(
+        mut self,
+        key: impl Into<String>,
+        value: impl Into<Vec<u8>>,
+        ttl: Duration,
+    ) -> Self {
+        self.ops.push(BatchOp::SetIfAbsent {
+            key: key.into(),
+            value: value.into(),
+            ttl,
+        });
+        self
+    }
+
+    /// Write `key` only if `predicate` holds for the current row (`None` if
+    /// absent), read inside the same transaction as the write — an atomic
+    /// read-modify-write without `get_for_update`'s async lock.
+    pub fn compare_and_set(
+        mut self,
+        key: impl Into<String>,
+        value: impl Into<Vec<u8>>,
+        ttl: Duration,
+        predicate: impl FnOnce(Option<&Value>) -> bool + 'static,
+    ) -> Self {
+        self.ops.push(BatchOp::CompareAndSet {
+            key: key.into(),
+            value: value.into(),
+            ttl,
+            predicate: Box::new(predicate),
+        });
+        self
+    }
+
+    /// Apply every queued operation inside one transaction, returning the
+    /// outcome of each in the order it was queued.
+    pub fn commit(self) -> Result<Vec<BatchOutcome>, rusqlite::Error> {
+        let topic = self.topic;
+        let conn = topic.inner.cache.inner.writer.lock().unwrap();
+        let tx = conn.unchecked_transaction()?;
+
+        let mut outcomes = Vec::with_capacity(self.ops.len());
+        let mut touched_keys = Vec::with_capacity(self.ops.len());
+        for op in self.ops {
+            match op {
+                BatchOp::Set { key, value, ttl } => {
+                    let (expiry, ttl) = topic.resolve_ttl(ttl);
+                    topic.write_row_in_tx(&tx, &key, &value, expiry, ttl)?;
+                    touched_keys.push(key);
+                    outcomes.push(BatchOutcome::Set);
+                }
+                BatchOp::Delete { key } => {
+                    topic.delete_row_in_tx(&tx, &key)?;
+                    touched_keys.push(key);
+                    outcomes.push(BatchOutcome::Deleted);
+                }
+                BatchOp::SetIfAbsent { key, value, ttl } => {
+                    if topic.read_row(&tx, &key)?.is_some() {
+                        outcomes.push(BatchOutcome::Skipped);
+                    } else {
+                        let (expiry, ttl) = topic.resolve_ttl(ttl);
+                        topic.write_row_in_tx(&tx, &key, &value, expiry, ttl)?;
+                        touched_keys.push(key);
+                        outcomes.push(BatchOutcome::Set);
+                    }
+                }
+                BatchOp::CompareAndSet {
+                    key,
+                    value,
+                    ttl,
+                    predicate,
+                } => {
+                    let existing = topic.read_row(&tx, &key)?;
+                    let holds = predicate(existing.as_ref().map(|(v, _)| v));
+                    if holds {
+                        let (expiry, ttl) = topic.resolve_ttl(ttl);
+                        topic.write_row_in_tx(&tx, &key, &value, expiry, ttl)?;
+                        touched_keys.push(key);
+                        outcomes.push(BatchOutcome::Set);
+                    } else {
+                        outcomes.push(BatchOutcome::Skipped);
+                    }
+                }
+            }
+        }
+
+        tx.commit()?;
+        for key in &touched_keys {
+            topic.clear_lazy_expiry(key);
+        }
+        Ok(outcomes)
+    }
+}
+
+impl Topic {
+    /// Start a batch of operations to commit atomically. See [`Batch`].
+    pub fn batch(&self) -> Batch<'_> {
+        Batch::new(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use rusqlite::Connection;
+
+    use crate::{Cache, CacheConfig};
+
+    use super::*;
+
+    fn new_topic() -> Topic {
+        Cache::new(CacheConfig::default(), Connection::open_in_memory().unwrap())
+            .unwrap()
+            .topic("t")
+            .unwrap()
+    }
+
+    #[test]
+    fn set_and_delete_commit_together() {
+        let topic = new_topic();
+        topic.set("keep-deleting", b"x", Duration::from_secs(60)).unwrap();
+
+        let outcomes = topic
+            .batch()
+            .set("a", b"1".to_vec(), Duration::from_secs(60))
+            .set("b", b"2".to_vec(), Duration::from_secs(60))
+            .delete("keep-deleting")
+            .commit()
+            .unwrap();
+
+        assert_eq!(
+            outcomes,
+            vec![BatchOutcome::Set, BatchOutcome::Set, BatchOutcome::Deleted]
+        );
+        assert_eq!(&topic.get("a").unwrap().unwrap().data[..], b"1");
+        assert_eq!(&topic.get("b").unwrap().unwrap().data[..], b"2");
+        assert!(topic.get("keep-deleting").unwrap().is_none());
+    }
+
+    #[test]
+    fn set_if_absent_only_writes_once() {
+        let topic = new_topic();
+
+        let first = topic
+            .batch()
+            .set_if_absent("k", b"first".to_vec(), Duration::from_secs(60))
+            .commit()
+            .unwrap();
+        assert_eq!(first, vec![BatchOutcome::Set]);
+
+        let second = topic
+            .batch()
+            .set_if_absent("k", b"second".to_vec(), Duration::from_secs(60))
+            .commit()
+            .unwrap();
+        assert_eq!(second, vec![BatchOutcome::Skipped]);
+        assert_eq!(&topic.get("k").unwrap().unwrap().data[..], b"first");
+    }
+
+    #[test]
+    fn compare_and_set_reads_and_writes_atomically() {
+        let topic = new_topic();
+        topic.set("counter", b"1", Duration::from_secs(60)).unwrap();
+
+        let outcome = topic
+            .batch()
+            .compare_and_set("counter", b"2".to_vec(), Duration::from_secs(60), |current| {
+                current.map(|v| &v.data[..] == b"1").unwrap_or(false)
+            })
+            .commit()
+            .unwrap();
+        assert_eq!(outcome, vec![BatchOutcome::Set]);
+        assert_eq!(&topic.get("counter").unwrap().unwrap().data[..], b"2");
+
+        let stale = topic
+            .batch()
+            .compare_and_set("counter", b"3".to_vec(), Duration::from_secs(60), |current| {
+                current.map(|v| &v.data[..] == b"1").unwrap_or(false)
+            })
+            .commit()
+            .unwrap();
+        assert_eq!(stale, vec![BatchOutcome::Skipped]);
+        assert_eq!(&topic.get("counter").unwrap().unwrap().data[..], b"2");
+    }
+
+    #[test]
+    fn get_many_batches_lookups() {
+        let topic = new_topic();
+        topic.set("a", b"1", Duration::from_secs(60)).unwrap();
+        topic.set("b", b"2", Duration::from_secs(60)).unwrap();
+
+        let values = topic.get_many(&["a", "missing", "b"]).unwrap();
+        assert_eq!(values[0].as_ref().unwrap().data, b"1");
+        assert!(values[1].is_none());
+        assert_eq!(values[2].as_ref().unwrap().data, b"2");
+    }
+}
@@ -0,0 +1,476 @@
+//! Opt-in content-defined chunking and block-level dedup for large values.
+//!
+//! When [`ChunkingConfig`] is set, values over `threshold` are split with a
+//! Gear-hash content-defined chunker, each chunk is content-addressed with
+//! blake3 and stored once in a per-cache `blocks` table keyed by hash, and
+//! the topic row stores an ordered manifest of chunk hashes instead of the
+//! raw bytes. Values at or under the threshold are still stored inline, just
+//! tagged with a marker byte so `get` knows how to decode them.
+
+use std::sync::OnceLock;
+
+use rusqlite::{params, Connection, OptionalExtension};
+
+type BlockHash = [u8; 32];
+
+const INLINE_MARKER: u8 = 0;
+const CHUNKED_MARKER: u8 = 1;
+
+#[derive(Clone, Debug)]
+pub struct ChunkingConfig {
+    /// Values larger than this many bytes are chunked; smaller values are
+    /// stored inline.
+    pub threshold: usize,
+    /// Target average chunk size. Actual sizes vary between `min_chunk_size`
+    /// and `max_chunk_size`.
+    pub avg_chunk_size: usize,
+    pub min_chunk_size: usize,
+    pub max_chunk_size: usize,
+}
+
+impl Default for ChunkingConfig {
+    fn default() -> Self {
+        ChunkingConfig {
+            threshold: 256 * 1024,
+            avg_chunk_size: 64 * 1024,
+            min_chunk_size: 16 * 1024,
+            max_chunk_size: 256 * 1024,
+        }
+    }
+}
+
+impl ChunkingConfig {
+    fn mask(&self) -> u64 {
+        (self.avg_chunk_size.max(2) as u64).next_power_of_two() - 1
+    }
+}
+
+pub(crate) fn ensure_blocks_table(conn: &Connection) -> Result<(), rusqlite::Error> {
+    conn.execute_batch(
+        "create table if not exists blocks (
+            hash blob primary key not null,
+            data blob not null,
+            refcount integer not null
+        );",
+    )
+}
+
+/// Split `data` on Gear-hash-defined boundaries: a boundary falls whenever
+/// the rolling hash of the trailing window has `mask` zeroed in its low
+/// bits, subject to `min_chunk_size`/`max_chunk_size` bounds.
+fn split_chunks<'a>(data: &'a [u8], cfg: &ChunkingConfig) -> Vec<&'a [u8]> {
+    let table = gear_table();
+    let mask = cfg.mask();
+    let mut chunks = Vec::new();
+    let mut start = 0usize;
+    let mut hash: u64 = 0;
+    for i in 0..data.len() {
+        hash = (hash << 1).wrapping_add(table[data[i] as usize]);
+        let len = i - start + 1;
+        if len >= cfg.min_chunk_size && (hash & mask == 0 || len >= cfg.max_chunk_size) {
+            chunks.push(&data[start..=i]);
+            start = i + 1;
+            hash = 0;
+        }
+    }
+    if start < data.len() {
+        chunks.push(&data[start..]);
+    }
+    chunks
+}
+
+fn gear_table() -> &'static [u64; 256] {
+    static TABLE: OnceLock<[u64; 256]> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        let mut table = [0u64; 256];
+        let mut seed: u64 = 0x9E3779B97F4A7C15;
+        for slot in table.iter_mut() {
+            seed = splitmix64(seed);
+            *slot = seed;
+        }
+        table
+    })
+}
+
+fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9E3779B97F4A7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+}
+
+pub(crate) fn encode_inline(value: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(1 + value.len());
+    out.push(INLINE_MARKER);
+    out.extend_from_slice(value);
+    out
+}
+
+fn encode_manifest(hashes: &[BlockHash]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(1 + 4 + hashes.len() * 32);
+    out.push(CHUNKED_MARKER);
+    out.extend_from_slice(&(hashes.len() as u32).to_le_bytes());
+    for h in hashes {
+        out.extend_from_slice(h);
+    }
+    out
+}
+
+fn decode_manifest(raw: &[u8]) -> Vec<BlockHash> {
+    let count = u32::from_le_bytes(raw[1..5].try_into().unwrap()) as usize;
+    let mut hashes = Vec::with_capacity(count);
+    for i in 0..count {
+        let start = 5 + i * 32;
+        hashes.push(raw[start..start + 32].try_into().unwrap());
+    }
+    hashes
+}
+
+/// Store a value, opening its own transaction. For use on the standalone
+/// `set`/`put_raw` paths, which don't already have one open. See
+/// [`store_value_in_tx`] for the batch path.
+pub(crate) fn store_value_at(
+    conn: &Connection,
+    table_name: &str,
+    key: &str,
+    value: &[u8],
+    created_at: Option<u64>,
+    expiry: u64,
+    ttl: u64,
+    cfg: Option<&ChunkingConfig>,
+) -> Result<(), rusqlite::Error> {
+    let tx = conn.unchecked_transaction()?;
+    store_value_in_tx(&tx, table_name, key, value, created_at, expiry, ttl, cfg)?;
+    tx.commit()
+}
+
+/// Store a value, chunking it when `cfg` is set and the value exceeds
+/// `cfg.threshold`, tagging it with the inline/chunked marker byte either
+/// way. Any chunks previously referenced by `key` are dereferenced first so
+/// overwrites don't leak refcounts. Zero-refcount blocks are only deleted
+/// after the new value's chunks are counted, so a chunk shared between the
+/// old and new value may drop to zero in between but is never deleted.
+///
+/// Assumes `tx` is already an open transaction (`Batch::commit`'s), unlike
+/// [`store_value_at`] — SQLite rejects a nested `BEGIN`.
+pub(crate) fn store_value_in_tx(
+    tx: &Connection,
+    table_name: &str,
+    key: &str,
+    value: &[u8],
+    created_at: Option<u64>,
+    expiry: u64,
+    ttl: u64,
+    cfg: Option<&ChunkingConfig>,
+) -> Result<(), rusqlite::Error> {
+    let old_v: Option<Vec<u8>> = tx
+        .query_row(
+            &format!("select v from {} where k = ?", table_name),
+            params![key],
+            |r| r.get(0),
+        )
+        .optional()?;
+    if let Some(old) = &old_v {
+        decref_if_chunked(tx, old)?;
+    }
+
+    let new_v = match cfg {
+        Some(cfg) if value.len() > cfg.threshold => {
+            let hashes = put_chunks(tx, value, cfg)?;
+            encode_manifest(&hashes)
+        }
+        _ => encode_inline(value),
+    };
+    let key_hash = crate::key_hash_sortable(crate::key_hash(key));
+    match created_at {
+        Some(created_at) => tx.execute(
+            &format!(
+                "replace into {} (k, v, created_at, expiry, ttl, key_hash) values(?, ?, ?, ?, ?, ?)",
+                table_name
+            ),
+            params![key, new_v, created_at, expiry, ttl, key_hash],
+        ),
+        None => tx.execute(
+            &format!(
+                "replace into {} (k, v, expiry, ttl, key_hash) values(?, ?, ?, ?, ?)",
+                table_name
+            ),
+            params![key, new_v, expiry, ttl, key_hash],
+        ),
+    }?;
+    gc_zero_refcount_blocks(tx)?;
+    Ok(())
+}
+
+fn put_chunks(
+    conn: &Connection,
+    value: &[u8],
+    cfg: &ChunkingConfig,
+) -> Result<Vec<BlockHash>, rusqlite::Error> {
+    let mut stmt = conn.prepare_cached(
+        "insert into blocks (hash, data, refcount) values (?, ?, 1)
+         on conflict(hash) do update set refcount = refcount + 1",
+    )?;
+    split_chunks(value, cfg)
+        .into_iter()
+        .map(|chunk| {
+            let hash = *blake3::hash(chunk).as_bytes();
+            stmt.execute(params![&hash[..], chunk])?;
+            Ok(hash)
+        })
+        .collect()
+}
+
+fn decref_if_chunked(conn: &Connection, raw: &[u8]) -> Result<(), rusqlite::Error> {
+    if raw.first() != Some(&CHUNKED_MARKER) {
+        return Ok(());
+    }
+    let mut stmt = conn.prepare_cached("update blocks set refcount = refcount - 1 where hash = ?")?;
+    for hash in decode_manifest(raw) {
+        stmt.execute(params![&hash[..]])?;
+    }
+    Ok(())
+}
+
+fn gc_zero_refcount_blocks(conn: &Connection) -> Result<usize, rusqlite::Error> {
+    conn.execute("delete from blocks where refcount <= 0", params![])
+}
+
+/// Reassemble the original value from a row's stored bytes: inline values
+/// are returned as-is (minus the marker), chunked values are read back from
+/// `blocks` in manifest order. Always goes by the marker byte actually
+/// stored, not the caller's current `ChunkingConfig` — a row written under a
+/// different config must still decode correctly.
+pub(crate) fn read_value(conn: &Connection, raw: &[u8]) -> Result<Vec<u8>, rusqlite::Error> {
+    match raw.first() {
+        Some(&INLINE_MARKER) => Ok(raw[1..].to_vec()),
+        Some(&CHUNKED_MARKER) => {
+            let mut stmt = conn.prepare_cached("select data from blocks where hash = ?")?;
+            let mut out = Vec::new();
+            for hash in decode_manifest(raw) {
+                let data: Vec<u8> = stmt.query_row(params![&hash[..]], |r| r.get(0))?;
+                out.extend_from_slice(&data);
+            }
+            Ok(out)
+        }
+        _ => Ok(raw.to_vec()),
+    }
+}
+
+/// Delete a row, opening its own transaction. See [`store_value_at`]/
+/// [`delete_value_in_tx`] for why there are two variants.
+pub(crate) fn delete_value(
+    conn: &Connection,
+    table_name: &str,
+    key: &str,
+) -> Result<(), rusqlite::Error> {
+    let tx = conn.unchecked_transaction()?;
+    delete_value_in_tx(&tx, table_name, key)?;
+    tx.commit()
+}
+
+/// Delete a row and dereference any chunks it held, assuming `tx` is
+/// already an open transaction.
+pub(crate) fn delete_value_in_tx(
+    tx: &Connection,
+    table_name: &str,
+    key: &str,
+) -> Result<(), rusqlite::Error> {
+    let old_v: Option<Vec<u8>> = tx
+        .query_row(
+            &format!("select v from {} where k = ?", table_name),
+            params![key],
+            |r| r.get(0),
+        )
+        .optional()?;
+    if let Some(old) = &old_v {
+        decref_if_chunked(tx, old)?;
+    }
+    tx.execute(
+        &format!("delete from {} where k = ?", table_name),
+        params![key],
+    )?;
+    gc_zero_refcount_blocks(tx)?;
+    Ok(())
+}
+
+/// Delete expired rows from `table`, dereferencing any chunks they held.
+pub(crate) fn gc_expired(
+    conn: &Connection,
+    table_name: &str,
+    now: u64,
+) -> Result<usize, rusqlite::Error> {
+    let tx = conn.unchecked_transaction()?;
+    let expired: Vec<Vec<u8>> = tx
+        .prepare(&format!("select v from {} where expiry < ?", table_name))?
+        .query_map(params![now], |r| r.get(0))?
+        .collect::<Result<_, _>>()?;
+    for v in &expired {
+        decref_if_chunked(&tx, v)?;
+    }
+    let count = tx.execute(
+        &format!("delete from {} where expiry < ?", table_name),
+        params![now],
+    )?;
+    gc_zero_refcount_blocks(&tx)?;
+    tx.commit()?;
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        path::PathBuf,
+        sync::atomic::{AtomicU64, Ordering},
+        time::Duration,
+    };
+
+    use rusqlite::Connection;
+
+    use crate::{Cache, CacheConfig};
+
+    use super::*;
+
+    fn chunked_cache() -> Cache {
+        Cache::new(
+            CacheConfig {
+                chunking: Some(ChunkingConfig {
+                    threshold: 64,
+                    avg_chunk_size: 16,
+                    min_chunk_size: 8,
+                    max_chunk_size: 32,
+                }),
+                ..CacheConfig::default()
+            },
+            Connection::open_in_memory().unwrap(),
+        )
+        .unwrap()
+    }
+
+    fn temp_db_path(name: &str) -> PathBuf {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        std::env::temp_dir().join(format!(
+            "sqlite_cache_chunking_test_{}_{}_{}.db",
+            name,
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed),
+        ))
+    }
+
+    #[test]
+    fn round_trips_chunked_and_inline_values() {
+        let cache = chunked_cache();
+        let topic = cache.topic("t").unwrap();
+
+        let small = b"short value";
+        topic.set("small", small, Duration::from_secs(60)).unwrap();
+        assert_eq!(&topic.get("small").unwrap().unwrap().data[..], small);
+
+        let large = vec![7u8; 4096];
+        topic.set("large", &large, Duration::from_secs(60)).unwrap();
+        assert_eq!(topic.get("large").unwrap().unwrap().data, large);
+    }
+
+    #[test]
+    fn shared_chunks_are_deduplicated_and_refcounted() {
+        let cache = chunked_cache();
+        let topic = cache.topic("t").unwrap();
+
+        let shared_prefix = vec![1u8; 2048];
+        let mut a = shared_prefix.clone();
+        a.extend_from_slice(&[2u8; 512]);
+        let mut b = shared_prefix.clone();
+        b.extend_from_slice(&[3u8; 512]);
+
+        topic.set("a", &a, Duration::from_secs(60)).unwrap();
+        topic.set("b", &b, Duration::from_secs(60)).unwrap();
+
+        let block_count: i64 = cache
+            .inner
+            .writer
+            .lock()
+            .unwrap()
+            .query_row("select count(*) from blocks", [], |r| r.get(0))
+            .unwrap();
+        let total_refcount: i64 = cache
+            .inner
+            .writer
+            .lock()
+            .unwrap()
+            .query_row("select sum(refcount) from blocks", [], |r| r.get(0))
+            .unwrap();
+        // The two values share their first chunk(s), so some block must be
+        // referenced by both rows.
+        assert!(total_refcount > block_count);
+
+        topic.delete("a").unwrap();
+        assert_eq!(topic.get("b").unwrap().unwrap().data, b);
+
+        topic.delete("b").unwrap();
+        let remaining: i64 = cache
+            .inner
+            .writer
+            .lock()
+            .unwrap()
+            .query_row("select count(*) from blocks", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(remaining, 0);
+    }
+
+    #[test]
+    fn rows_survive_chunking_being_toggled_off_across_a_reopen() {
+        let path = temp_db_path("toggle");
+        let large = vec![9u8; 4096];
+
+        {
+            let cache = Cache::new(
+                CacheConfig {
+                    chunking: Some(ChunkingConfig {
+                        threshold: 64,
+                        avg_chunk_size: 16,
+                        min_chunk_size: 8,
+                        max_chunk_size: 32,
+                    }),
+                    ..CacheConfig::default()
+                },
+                Connection::open(&path).unwrap(),
+            )
+            .unwrap();
+            let topic = cache.topic("t").unwrap();
+            topic.set("large", &large, Duration::from_secs(3600)).unwrap();
+            topic.set("small", b"hi", Duration::from_secs(3600)).unwrap();
+        }
+
+        {
+            // Reopen the same database with chunking disabled. Decoding goes
+            // by each row's own marker byte, not this process's config, so
+            // both rows above must still come back intact.
+            let cache =
+                Cache::new(CacheConfig::default(), Connection::open(&path).unwrap()).unwrap();
+            let topic = cache.topic("t").unwrap();
+            assert_eq!(topic.get("large").unwrap().unwrap().data, large);
+            assert_eq!(&topic.get("small").unwrap().unwrap().data[..], b"hi");
+
+            // A fresh write while chunking is off round-trips as plain inline
+            // data.
+            topic.set("new", b"plain", Duration::from_secs(3600)).unwrap();
+            assert_eq!(&topic.get("new").unwrap().unwrap().data[..], b"plain");
+
+            // Deleting the pre-existing chunked row must still dereference
+            // its blocks instead of leaking them.
+            topic.delete("large").unwrap();
+            let remaining: i64 = cache
+                .inner
+                .writer
+                .lock()
+                .unwrap()
+                .query_row("select count(*) from blocks", [], |r| r.get(0))
+                .unwrap();
+            assert_eq!(remaining, 0);
+        }
+
+        for ext in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), ext));
+        }
+    }
+}
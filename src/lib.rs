@@ -1,13 +1,21 @@
 #[cfg(test)]
 mod lib_test;
 
+pub mod batch;
+pub mod chunking;
+pub mod scan;
+pub mod sync;
+
 use data_encoding::BASE32_NOPAD;
 use futures::channel::oneshot::{channel, Receiver, Sender};
 pub use rusqlite;
 
 use std::{
     collections::HashMap,
-    sync::{mpsc, Arc, Mutex, Weak},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc, Mutex, Weak,
+    },
     time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
@@ -23,6 +31,15 @@ pub struct CacheConfig {
     pub flush_interval: Duration,
     pub flush_gc_ratio: u64,
     pub max_ttl: Option<Duration>,
+    /// Number of dedicated read-only connections to keep open alongside the
+    /// writer connection. Ignored for in-memory databases, which cannot be
+    /// shared across connections and always fall back to a single
+    /// connection for both reads and writes.
+    pub read_pool_size: usize,
+    /// When set, values larger than the configured threshold are split into
+    /// content-defined chunks and deduplicated in a shared `blocks` table.
+    /// See [`chunking::ChunkingConfig`].
+    pub chunking: Option<chunking::ChunkingConfig>,
 }
 
 impl Default for CacheConfig {
@@ -31,6 +48,8 @@ impl Default for CacheConfig {
             flush_interval: Duration::from_secs(10),
             flush_gc_ratio: 30,
             max_ttl: None,
+            read_pool_size: 4,
+            chunking: None,
         }
     }
 }
@@ -42,7 +61,8 @@ pub struct Topic {
 
 struct CacheImpl {
     config: CacheConfig,
-    conn: Mutex<Connection>,
+    writer: Mutex<Connection>,
+    readers: ReaderPool,
     lazy_expiry_update: Mutex<HashMap<(Arc<str>, String), u64>>,
     stop_tx: Mutex<mpsc::Sender<()>>,
     completion_rx: Mutex<mpsc::Receiver<()>>,
@@ -61,14 +81,139 @@ impl Drop for CacheImpl {
     }
 }
 
+/// Pool of read-only connections used to serve `get`/`get_for_update` reads
+/// in parallel with writes. Under WAL mode readers never block on the
+/// writer, so spreading reads across several connections lets concurrent
+/// lookups actually run concurrently instead of queueing on one `Mutex`.
+///
+/// In-memory databases cannot be opened from more than one connection, so
+/// `ReaderPool::Shared` reuses the single writer connection for reads in
+/// that case, matching the previous single-mutex behavior.
+enum ReaderPool {
+    Shared,
+    Pool {
+        conns: Vec<Mutex<Connection>>,
+        next: AtomicUsize,
+        spill: SpillPool,
+    },
+}
+
+impl ReaderPool {
+    fn new(conn: &Connection, size: usize) -> Result<Self, rusqlite::Error> {
+        let path = match conn.path() {
+            Some(p) if !p.is_empty() && size > 0 => p.to_string(),
+            _ => return Ok(ReaderPool::Shared),
+        };
+        let mut conns = Vec::with_capacity(size);
+        for _ in 0..size {
+            conns.push(Mutex::new(Self::open_reader(&path)?));
+        }
+        Ok(ReaderPool::Pool {
+            conns,
+            next: AtomicUsize::new(0),
+            spill: SpillPool::new(path),
+        })
+    }
+
+    fn open_reader(path: &str) -> Result<Connection, rusqlite::Error> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch("pragma query_only = true;")?;
+        Ok(conn)
+    }
+
+    /// Run `f` against a free reader connection, falling back to the writer
+    /// connection when running against an in-memory database. Readers are
+    /// picked round-robin and `try_lock`-scanned so a busy reader never
+    /// blocks a caller that could be served by another one; on-demand
+    /// "spill" connections absorb bursts once the whole pool is busy.
+    fn with<R>(&self, writer: &Mutex<Connection>, f: impl FnOnce(&Connection) -> R) -> R {
+        match self {
+            ReaderPool::Shared => {
+                let conn = writer.lock().unwrap();
+                f(&conn)
+            }
+            ReaderPool::Pool { conns, next, spill } => {
+                let start = next.fetch_add(1, Ordering::Relaxed);
+                for i in 0..conns.len() {
+                    if let Ok(conn) = conns[(start + i) % conns.len()].try_lock() {
+                        return f(&conn);
+                    }
+                }
+                if let Some(conn) = spill.acquire() {
+                    let result = f(&conn);
+                    spill.release(conn);
+                    return result;
+                }
+                let conn = conns[start % conns.len()].lock().unwrap();
+                f(&conn)
+            }
+        }
+    }
+}
+
+/// Bounded pool of extra reader connections opened on demand when the fixed
+/// `ReaderPool` is saturated, and recycled through a channel for reuse by
+/// the next caller instead of being closed immediately.
+struct SpillPool {
+    path: String,
+    rx: Mutex<mpsc::Receiver<Connection>>,
+    tx: mpsc::Sender<Connection>,
+    outstanding: AtomicUsize,
+    max: usize,
+}
+
+impl SpillPool {
+    const MAX_SPILL: usize = 8;
+
+    fn new(path: String) -> Self {
+        let (tx, rx) = mpsc::channel();
+        SpillPool {
+            path,
+            rx: Mutex::new(rx),
+            tx,
+            outstanding: AtomicUsize::new(0),
+            max: Self::MAX_SPILL,
+        }
+    }
+
+    fn acquire(&self) -> Option<Connection> {
+        if let Ok(conn) = self.rx.lock().unwrap().try_recv() {
+            self.outstanding.fetch_add(1, Ordering::Relaxed);
+            return Some(conn);
+        }
+        if self.outstanding.fetch_add(1, Ordering::Relaxed) >= self.max {
+            self.outstanding.fetch_sub(1, Ordering::Relaxed);
+            return None;
+        }
+        match ReaderPool::open_reader(&self.path) {
+            Ok(conn) => Some(conn),
+            Err(e) => {
+                self.outstanding.fetch_sub(1, Ordering::Relaxed);
+                tracing::error!(error = %e, "failed to open spill reader connection");
+                None
+            }
+        }
+    }
+
+    fn release(&self, conn: Connection) {
+        self.outstanding.fetch_sub(1, Ordering::Relaxed);
+        let _ = self.tx.send(conn);
+    }
+}
+
 impl Cache {
     pub fn new(config: CacheConfig, conn: Connection) -> Result<Self, rusqlite::Error> {
         assert!(config.flush_gc_ratio > 0);
         let (stop_tx, stop_rx) = mpsc::channel::<()>();
         let (completion_tx, completion_rx) = mpsc::channel::<()>();
         conn.execute_batch("pragma journal_mode = wal;")?;
+        // Unconditional: rows written under any past config may still
+        // reference blocks, regardless of whether chunking is on now.
+        chunking::ensure_blocks_table(&conn)?;
+        let readers = ReaderPool::new(&conn, config.read_pool_size)?;
         let inner = Arc::new(CacheImpl {
-            conn: Mutex::new(conn),
+            writer: Mutex::new(conn),
+            readers,
             config: config.clone(),
             lazy_expiry_update: Mutex::new(HashMap::new()),
             stop_tx: Mutex::new(stop_tx),
@@ -82,7 +227,7 @@ impl Cache {
     fn flush(&self) {
         let lazy_expiry_update = std::mem::take(&mut *self.inner.lazy_expiry_update.lock().unwrap());
         for ((table_name, key), expiry) in lazy_expiry_update {
-            let res = self.inner.conn.lock().unwrap().execute(
+            let res = self.inner.writer.lock().unwrap().execute(
                 &format!("update {} set expiry = ? where k = ?", table_name),
                 rusqlite::params![expiry, key],
             );
@@ -93,13 +238,10 @@ impl Cache {
     }
 
     fn gc(&self) -> Result<(), rusqlite::Error> {
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
+        let now = now_secs();
         let tables = self
             .inner
-            .conn
+            .writer
             .lock()
             .unwrap()
             .unchecked_transaction()?
@@ -108,10 +250,7 @@ impl Cache {
             .collect::<Result<Vec<String>, rusqlite::Error>>()?;
         let mut total = 0usize;
         for table in tables {
-            let count = self.inner.conn.lock().unwrap().execute(
-                &format!("delete from {} where expiry < ?", table),
-                rusqlite::params![now],
-            )?;
+            let count = chunking::gc_expired(&self.inner.writer.lock().unwrap(), &table, now)?;
             total += count;
         }
         if total != 0 {
@@ -122,21 +261,31 @@ impl Cache {
 
     pub fn topic(&self, key: &str) -> Result<Topic, rusqlite::Error> {
         let table_name = format!("topic_{}", BASE32_NOPAD.encode(key.as_bytes()));
-        self.inner.conn.lock().unwrap().execute_batch(&format!(
+        let conn = self.inner.writer.lock().unwrap();
+        let tx = conn.unchecked_transaction()?;
+        tx.execute_batch(&format!(
             r#"
-begin transaction;
 create table if not exists {} (
     k text primary key not null,
     v blob not null,
     created_at integer not null default (cast(strftime('%s', 'now') as integer)),
     expiry integer not null,
-    ttl integer not null
+    ttl integer not null,
+    key_hash integer not null default 0
 );
 create index if not exists {}_by_expiry on {} (expiry);
-commit;
 "#,
             table_name, table_name, table_name,
         ))?;
+        // `create table if not exists` is a no-op on a table that already
+        // exists from before the key_hash column landed, so it has to be
+        // migrated in separately.
+        ensure_key_hash_column(&tx, &table_name)?;
+        tx.execute_batch(&format!(
+            "create index if not exists {0}_by_key_hash on {0} (key_hash);",
+            table_name,
+        ))?;
+        tx.commit()?;
         Ok(Topic {
             inner: Arc::new(TopicImpl {
                 cache: self.clone(),
@@ -147,6 +296,38 @@ commit;
     }
 }
 
+/// Add and backfill the `key_hash` column on a topic table created before
+/// sync support landed. A no-op once the column is present.
+fn ensure_key_hash_column(conn: &Connection, table_name: &str) -> Result<(), rusqlite::Error> {
+    let has_key_hash = conn
+        .prepare(&format!("pragma table_info({})", table_name))?
+        .query_map([], |row| row.get::<_, String>(1))?
+        .collect::<Result<Vec<String>, rusqlite::Error>>()?
+        .iter()
+        .any(|name| name == "key_hash");
+    if has_key_hash {
+        return Ok(());
+    }
+
+    conn.execute(
+        &format!(
+            "alter table {} add column key_hash integer not null default 0",
+            table_name
+        ),
+        [],
+    )?;
+    let keys: Vec<String> = conn
+        .prepare(&format!("select k from {}", table_name))?
+        .query_map([], |row| row.get(0))?
+        .collect::<Result<_, _>>()?;
+    let mut stmt =
+        conn.prepare_cached(&format!("update {} set key_hash = ? where k = ?", table_name))?;
+    for k in keys {
+        stmt.execute(rusqlite::params![key_hash_sortable(key_hash(&k)), k])?;
+    }
+    Ok(())
+}
+
 pub struct Value {
     pub data: Vec<u8>,
     pub created_at: u64,
@@ -154,36 +335,153 @@ pub struct Value {
 
 impl Topic {
     pub fn get(&self, key: &str) -> Result<Option<Value>, rusqlite::Error> {
-        let conn = self.inner.cache.inner.conn.lock().unwrap();
+        let row = self
+            .inner
+            .cache
+            .inner
+            .readers
+            .with(&self.inner.cache.inner.writer, |conn| self.read_row(conn, key))?;
+        if let Some((value, ttl)) = row {
+            self.bump_lazy_expiry(key, ttl);
+            Ok(Some(value))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Read a row and decode it (dechunking if needed), without touching
+    /// lazy-expiry bookkeeping. Shared by `get`, `get_many`, and `batch`.
+    fn read_row(
+        &self,
+        conn: &Connection,
+        key: &str,
+    ) -> Result<Option<(Value, u64)>, rusqlite::Error> {
         let mut stmt = conn.prepare_cached(&format!(
             "select v, created_at, ttl from {} where k = ?",
             self.inner.table_name,
         ))?;
-        let rsp: Option<(Vec<u8>, u64, u64)> = stmt
+        let row: Option<(Vec<u8>, u64, u64)> = stmt
             .query_row(rusqlite::params![key], |x| {
                 Ok((x.get(0)?, x.get(1)?, x.get(2)?))
             })
             .optional()?;
-        if let Some((data, created_at, ttl)) = rsp {
-            self.inner
-                .cache
-                .inner
-                .lazy_expiry_update
-                .lock()
-                .unwrap()
-                .insert(
-                    (self.inner.table_name.clone(), key.to_string()),
-                    SystemTime::now()
-                        .duration_since(UNIX_EPOCH)
-                        .unwrap()
-                        .as_secs()
-                        .saturating_add(ttl)
-                        .min(i64::MAX as u64),
-                );
-            Ok(Some(Value { data, created_at }))
-        } else {
-            Ok(None)
+        row.map(|(raw, created_at, ttl)| {
+            let data = chunking::read_value(conn, &raw)?;
+            Ok((Value { data, created_at }, ttl))
+        })
+        .transpose()
+    }
+
+    /// Write a row, chunking it first if chunking is configured. Used by
+    /// `set`, which doesn't already have a transaction open.
+    fn write_row(
+        &self,
+        conn: &Connection,
+        key: &str,
+        value: &[u8],
+        expiry: u64,
+        ttl: u64,
+    ) -> Result<(), rusqlite::Error> {
+        chunking::store_value_at(
+            conn,
+            &self.inner.table_name,
+            key,
+            value,
+            None,
+            expiry,
+            ttl,
+            self.inner.cache.inner.config.chunking.as_ref(),
+        )
+    }
+
+    /// Same as `write_row`, but for `Batch::commit`, which already has a
+    /// transaction open and must not start a nested one.
+    fn write_row_in_tx(
+        &self,
+        tx: &Connection,
+        key: &str,
+        value: &[u8],
+        expiry: u64,
+        ttl: u64,
+    ) -> Result<(), rusqlite::Error> {
+        chunking::store_value_in_tx(
+            tx,
+            &self.inner.table_name,
+            key,
+            value,
+            None,
+            expiry,
+            ttl,
+            self.inner.cache.inner.config.chunking.as_ref(),
+        )
+    }
+
+    /// Delete a row, dereferencing its chunks first if it was chunked. Used
+    /// by `delete`, which doesn't already have a transaction open.
+    fn delete_row(&self, conn: &Connection, key: &str) -> Result<(), rusqlite::Error> {
+        chunking::delete_value(conn, &self.inner.table_name, key)
+    }
+
+    /// Same as `delete_row`, but for `Batch::commit`. See `write_row_in_tx`.
+    fn delete_row_in_tx(&self, tx: &Connection, key: &str) -> Result<(), rusqlite::Error> {
+        chunking::delete_value_in_tx(tx, &self.inner.table_name, key)
+    }
+
+    /// Resolve a caller-supplied ttl into the `(expiry, ttl)` pair stored in
+    /// the row, applying `max_ttl` and the same saturating-add clamp `set`
+    /// has always used.
+    fn resolve_ttl(&self, ttl: Duration) -> (u64, u64) {
+        let mut ttl = ttl.as_secs();
+        if let Some(max_ttl) = self.inner.cache.inner.config.max_ttl {
+            ttl = ttl.min(max_ttl.as_secs());
         }
+        ttl = ttl.min(i64::MAX as u64);
+        let expiry = now_secs().saturating_add(ttl).min(i64::MAX as u64);
+        (expiry, ttl)
+    }
+
+    fn bump_lazy_expiry(&self, key: &str, ttl: u64) {
+        self.inner
+            .cache
+            .inner
+            .lazy_expiry_update
+            .lock()
+            .unwrap()
+            .insert(
+                (self.inner.table_name.clone(), key.to_string()),
+                now_secs().saturating_add(ttl).min(i64::MAX as u64),
+            );
+    }
+
+    fn clear_lazy_expiry(&self, key: &str) {
+        self.inner
+            .cache
+            .inner
+            .lazy_expiry_update
+            .lock()
+            .unwrap()
+            .remove(&(self.inner.table_name.clone(), key.to_string()));
+    }
+
+    /// Look up several keys through a single prepared statement and reader
+    /// connection acquisition, instead of one `get` call per key.
+    pub fn get_many(&self, keys: &[&str]) -> Result<Vec<Option<Value>>, rusqlite::Error> {
+        let rows = self.inner.cache.inner.readers.with(
+            &self.inner.cache.inner.writer,
+            |conn| -> Result<Vec<Option<(Value, u64)>>, rusqlite::Error> {
+                keys.iter().map(|key| self.read_row(conn, key)).collect()
+            },
+        )?;
+        Ok(rows
+            .into_iter()
+            .zip(keys)
+            .map(|(row, key)| {
+                row.map(|(value, ttl)| {
+                    self.bump_lazy_expiry(key, ttl);
+                    value
+                })
+            })
+            .collect())
     }
 
     pub async fn get_for_update(
@@ -222,42 +520,16 @@ impl Topic {
     }
 
     pub fn set(&self, key: &str, value: &[u8], ttl: Duration) -> Result<(), rusqlite::Error> {
-        let conn = self.inner.cache.inner.conn.lock().unwrap();
-        let mut stmt = conn.prepare_cached(&format!(
-            "replace into {} (k, v, expiry, ttl) values(?, ?, ?, ?)",
-            self.inner.table_name
-        ))?;
-        let mut ttl = ttl.as_secs();
-        if let Some(max_ttl) = self.inner.cache.inner.config.max_ttl {
-            let max_ttl = max_ttl.as_secs();
-            ttl = ttl.min(max_ttl);
-        }
-        ttl = ttl.min(i64::MAX as u64);
-        let expiry = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs()
-            .saturating_add(ttl)
-            .min(i64::MAX as u64);
-        stmt.execute(rusqlite::params![key, value, expiry, ttl])?;
-        self.inner
-            .cache
-            .inner
-            .lazy_expiry_update
-            .lock()
-            .unwrap()
-            .remove(&(self.inner.table_name.clone(), key.to_string()));
+        let (expiry, ttl) = self.resolve_ttl(ttl);
+        let conn = self.inner.cache.inner.writer.lock().unwrap();
+        self.write_row(&conn, key, value, expiry, ttl)?;
+        self.clear_lazy_expiry(key);
         Ok(())
     }
 
     pub fn delete(&self, key: &str) -> Result<(), rusqlite::Error> {
-        let conn = self.inner.cache.inner.conn.lock().unwrap();
-        let mut stmt = conn.prepare_cached(&format!(
-            "delete from {} where k = ?",
-            self.inner.table_name
-        ))?;
-        stmt.execute(rusqlite::params![key])?;
-        Ok(())
+        let conn = self.inner.cache.inner.writer.lock().unwrap();
+        self.delete_row(&conn, key)
     }
 }
 
@@ -280,6 +552,31 @@ impl KeyUpdater {
     }
 }
 
+pub(crate) fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// Deterministic hash of a row's key into the 64-bit space `sync::HashRange`
+/// partitions. Stored alongside each row (the `key_hash` column) so range
+/// queries during sync can be pushed down to an indexed SQL predicate
+/// instead of scanning every row in the topic.
+pub(crate) fn key_hash(key: &str) -> u64 {
+    let hash = blake3::hash(key.as_bytes());
+    u64::from_be_bytes(hash.as_bytes()[..8].try_into().unwrap())
+}
+
+/// Map a `u64` key hash onto the `i64` space SQLite actually stores, flipping
+/// the sign bit so ordinary signed `<`/`>=` comparisons (and the index built
+/// over this column) preserve unsigned ordering. Plain `as i64` would also
+/// round-trip, but `rusqlite`'s `ToSql` for `u64` rejects values above
+/// `i64::MAX` outright, and about half of all hashes land there.
+pub(crate) fn key_hash_sortable(hash: u64) -> i64 {
+    (hash ^ 0x8000_0000_0000_0000) as i64
+}
+
 fn periodic_task(
     config: CacheConfig,
     stop_rx: mpsc::Receiver<()>,
@@ -66,6 +66,91 @@ async fn test_update_lock() {
     assert_eq!(&value.data[..], b"world");
 }
 
+/// A topic table created before the `key_hash` column existed must be
+/// migrated in place: `create table if not exists` is a silent no-op
+/// against it, so `Cache::topic` has to detect and backfill the column
+/// itself.
+#[test]
+#[traced_test]
+fn test_topic_migrates_pre_existing_table_missing_key_hash() {
+    let conn = Connection::open_in_memory().unwrap();
+    let table_name = format!(
+        "topic_{}",
+        data_encoding::BASE32_NOPAD.encode("test-topic".as_bytes())
+    );
+    conn.execute_batch(&format!(
+        "create table {} (
+            k text primary key not null,
+            v blob not null,
+            created_at integer not null default (cast(strftime('%s', 'now') as integer)),
+            expiry integer not null,
+            ttl integer not null
+        );
+        insert into {} (k, v, expiry, ttl) values ('old-key', x'006f6c642d76616c7565', 9999999999, 60);",
+        table_name, table_name,
+    ))
+    .unwrap();
+
+    let cache = Cache::new(CacheConfig::default(), conn).unwrap();
+    let topic = cache.topic("test-topic").unwrap();
+    assert_eq!(&topic.get("old-key").unwrap().unwrap().data[..], b"old-value");
+
+    topic.set("new-key", b"new-value", Duration::from_secs(60)).unwrap();
+    assert_eq!(&topic.get("new-key").unwrap().unwrap().data[..], b"new-value");
+}
+
+/// `:memory:` databases always take `ReaderPool::Shared`, so this exercises
+/// the real `ReaderPool::Pool`/`SpillPool` code paths against a file-backed
+/// database instead: with a single fixed reader connection and eight
+/// concurrent readers, every `get` that finds that connection busy is
+/// served by one of the spill pool's on-demand connections.
+#[test]
+#[traced_test]
+fn test_reader_pool_and_spill_against_file_backed_db() {
+    let path = std::env::temp_dir().join(format!(
+        "sqlite_cache_reader_pool_test_{}.db",
+        std::process::id()
+    ));
+    let _ = std::fs::remove_file(&path);
+
+    let cache = Cache::new(
+        CacheConfig {
+            read_pool_size: 1,
+            ..CacheConfig::default()
+        },
+        Connection::open(&path).unwrap(),
+    )
+    .unwrap();
+    let topic = cache.topic("test-topic").unwrap();
+    for i in 0..100 {
+        topic
+            .set(&format!("k{}", i), format!("v{}", i).as_bytes(), Duration::from_secs(60))
+            .unwrap();
+    }
+
+    let handles: Vec<_> = (0..8)
+        .map(|_| {
+            let topic = topic.clone();
+            std::thread::spawn(move || {
+                for _ in 0..50 {
+                    for i in 0..100 {
+                        let value = topic.get(&format!("k{}", i)).unwrap().unwrap();
+                        assert_eq!(value.data, format!("v{}", i).as_bytes());
+                    }
+                }
+            })
+        })
+        .collect();
+    for h in handles {
+        h.join().unwrap();
+    }
+
+    drop(cache);
+    for ext in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{}", path.display(), ext));
+    }
+}
+
 #[traced_test]
 #[tokio::test]
 async fn test_gc() {
@@ -0,0 +1,264 @@
+//! Ordered iteration, prefix, and range scans over a `Topic`.
+//!
+//! `Topic::get` only supports point lookups; `scan`/`scan_prefix` add
+//! ascending-key iteration backed by keyset pagination (`k > ?last order by
+//! k limit ?batch`) so a single iterator never holds the connection mutex
+//! for longer than one batch fetch, just like individual `get` calls.
+
+use std::{
+    collections::VecDeque,
+    ops::{Bound, RangeBounds},
+};
+
+use rusqlite::ToSql;
+
+use crate::{chunking, now_secs, Topic, Value};
+
+const DEFAULT_BATCH_SIZE: usize = 256;
+
+fn to_owned_bound(bound: Bound<&&str>) -> Bound<String> {
+    match bound {
+        Bound::Included(s) => Bound::Included((*s).to_string()),
+        Bound::Excluded(s) => Bound::Excluded((*s).to_string()),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+fn escape_like(prefix: &str) -> String {
+    let mut out = String::with_capacity(prefix.len());
+    for c in prefix.chars() {
+        if c == '\\' || c == '%' || c == '_' {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// A cursor over a `Topic`'s rows in ascending key order, yielded in small
+/// batches. Rows whose `expiry` has passed are skipped transparently, and
+/// each yielded row refreshes its lazy-expiry bump exactly like `get` does.
+pub struct ScanIter {
+    topic: Topic,
+    next_start: Bound<String>,
+    end: Bound<String>,
+    like_prefix: Option<String>,
+    batch_size: usize,
+    buffer: VecDeque<(String, Vec<u8>, u64, u64)>,
+    exhausted: bool,
+}
+
+impl ScanIter {
+    fn new(topic: Topic, start: Bound<String>, end: Bound<String>, like_prefix: Option<String>) -> Self {
+        ScanIter {
+            topic,
+            next_start: start,
+            end,
+            like_prefix,
+            batch_size: DEFAULT_BATCH_SIZE,
+            buffer: VecDeque::new(),
+            exhausted: false,
+        }
+    }
+
+    fn fetch_batch(&mut self) -> Result<(), rusqlite::Error> {
+        let now = now_secs();
+        let table_name = self.topic.inner.table_name.clone();
+
+        let mut clauses = vec!["expiry >= ?".to_string()];
+        let mut params: Vec<Box<dyn ToSql>> = vec![Box::new(now)];
+        match &self.next_start {
+            Bound::Included(k) => {
+                clauses.push("k >= ?".to_string());
+                params.push(Box::new(k.clone()));
+            }
+            Bound::Excluded(k) => {
+                clauses.push("k > ?".to_string());
+                params.push(Box::new(k.clone()));
+            }
+            Bound::Unbounded => {}
+        }
+        match &self.end {
+            Bound::Included(k) => {
+                clauses.push("k <= ?".to_string());
+                params.push(Box::new(k.clone()));
+            }
+            Bound::Excluded(k) => {
+                clauses.push("k < ?".to_string());
+                params.push(Box::new(k.clone()));
+            }
+            Bound::Unbounded => {}
+        }
+        if let Some(prefix) = &self.like_prefix {
+            clauses.push("k like ? escape '\\'".to_string());
+            params.push(Box::new(format!("{}%", escape_like(prefix))));
+        }
+        params.push(Box::new(self.batch_size as i64));
+
+        let sql = format!(
+            "select k, v, created_at, ttl from {} where {} order by k limit ?",
+            table_name,
+            clauses.join(" and "),
+        );
+
+        let rows: Vec<(String, Vec<u8>, u64, u64)> = self.topic.inner.cache.inner.readers.with(
+            &self.topic.inner.cache.inner.writer,
+            |conn| -> Result<_, rusqlite::Error> {
+                let mut stmt = conn.prepare_cached(&sql)?;
+                let rows = stmt
+                    .query_map(
+                        rusqlite::params_from_iter(params.iter().map(|p| p.as_ref())),
+                        |x| Ok((x.get(0)?, x.get(1)?, x.get(2)?, x.get(3)?)),
+                    )?
+                    .collect();
+                rows
+            },
+        )?;
+
+        if rows.len() < self.batch_size {
+            self.exhausted = true;
+        }
+        if let Some((last_key, ..)) = rows.last() {
+            self.next_start = Bound::Excluded(last_key.clone());
+        }
+        self.buffer.extend(rows);
+        Ok(())
+    }
+}
+
+impl Iterator for ScanIter {
+    type Item = Result<(String, Value), rusqlite::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.buffer.is_empty() && !self.exhausted {
+            if let Err(e) = self.fetch_batch() {
+                return Some(Err(e));
+            }
+        }
+        let (key, raw, created_at, ttl) = self.buffer.pop_front()?;
+
+        let result = self.topic.inner.cache.inner.readers.with(
+            &self.topic.inner.cache.inner.writer,
+            |conn| chunking::read_value(conn, &raw),
+        );
+        let data = match result {
+            Ok(data) => data,
+            Err(e) => return Some(Err(e)),
+        };
+
+        self.topic.bump_lazy_expiry(&key, ttl);
+
+        Some(Ok((key, Value { data, created_at })))
+    }
+}
+
+impl Topic {
+    /// Iterate rows with keys in `range`, in ascending order. `range` is
+    /// generic over `&str` (not `str`) so ordinary literals like
+    /// `"a".."b"` work as arguments.
+    pub fn scan<'r>(&self, range: impl RangeBounds<&'r str>) -> ScanIter {
+        ScanIter::new(
+            self.clone(),
+            to_owned_bound(range.start_bound()),
+            to_owned_bound(range.end_bound()),
+            None,
+        )
+    }
+
+    /// Iterate rows whose key starts with `prefix`, in ascending order.
+    pub fn scan_prefix(&self, prefix: &str) -> ScanIter {
+        ScanIter::new(
+            self.clone(),
+            Bound::Included(prefix.to_string()),
+            Bound::Unbounded,
+            Some(prefix.to_string()),
+        )
+    }
+
+    /// Number of live (non-expired) rows in this topic.
+    pub fn count(&self) -> Result<u64, rusqlite::Error> {
+        let now = now_secs();
+        self.inner.cache.inner.readers.with(
+            &self.inner.cache.inner.writer,
+            |conn| -> Result<u64, rusqlite::Error> {
+                conn.query_row(
+                    &format!(
+                        "select count(*) from {} where expiry >= ?",
+                        self.inner.table_name
+                    ),
+                    rusqlite::params![now],
+                    |x| x.get(0),
+                )
+            },
+        )
+    }
+
+    /// Live keys starting with `prefix`, in ascending order.
+    pub fn keys_prefix(&self, prefix: &str) -> Result<Vec<String>, rusqlite::Error> {
+        self.scan_prefix(prefix)
+            .map(|r| r.map(|(k, _)| k))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use rusqlite::Connection;
+
+    use crate::{Cache, CacheConfig};
+
+    use super::*;
+
+    fn seeded_topic() -> Topic {
+        let cache = Cache::new(CacheConfig::default(), Connection::open_in_memory().unwrap()).unwrap();
+        let topic = cache.topic("t").unwrap();
+        for key in ["a/1", "a/2", "b/1", "c/1", "c/2", "c/3"] {
+            topic.set(key, key.as_bytes(), Duration::from_secs(3600)).unwrap();
+        }
+        topic
+    }
+
+    #[test]
+    fn scan_yields_ascending_keys() {
+        let topic = seeded_topic();
+        let keys: Vec<String> = topic
+            .scan(..)
+            .map(|r| r.unwrap().0)
+            .collect();
+        assert_eq!(keys, vec!["a/1", "a/2", "b/1", "c/1", "c/2", "c/3"]);
+    }
+
+    #[test]
+    fn scan_respects_range_bounds() {
+        let topic = seeded_topic();
+        let keys: Vec<String> = topic
+            .scan("a/2".."c/1")
+            .map(|r| r.unwrap().0)
+            .collect();
+        assert_eq!(keys, vec!["a/2", "b/1"]);
+    }
+
+    #[test]
+    fn scan_prefix_and_helpers() {
+        let topic = seeded_topic();
+        assert_eq!(topic.keys_prefix("c/").unwrap(), vec!["c/1", "c/2", "c/3"]);
+        assert_eq!(topic.count().unwrap(), 6);
+
+        topic.delete("c/2").unwrap();
+        assert_eq!(topic.keys_prefix("c/").unwrap(), vec!["c/1", "c/3"]);
+        assert_eq!(topic.count().unwrap(), 5);
+    }
+
+    #[test]
+    fn scan_skips_expired_rows() {
+        let topic = seeded_topic();
+        topic
+            .set("expired", b"gone", Duration::from_secs(0))
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(1100));
+        let keys: Vec<String> = topic.scan(..).map(|r| r.unwrap().0).collect();
+        assert!(!keys.contains(&"expired".to_string()));
+    }
+}